@@ -1,10 +1,11 @@
 extern crate libc;
 extern crate bigint;
 extern crate sputnikvm;
+extern crate tiny_keccak;
 
 mod common;
 
-pub use common::{c_address, c_gas, c_u256, c_h256};
+pub use common::{c_address, c_gas, c_u256, c_h256, c_u512, c_i256, c_bloom, c_hex_status};
 
 use std::slice;
 use std::ptr;
@@ -12,11 +13,12 @@ use std::rc::Rc;
 use std::ops::DerefMut;
 use std::collections::HashMap;
 use libc::{c_uchar, c_uint, c_longlong};
-use bigint::{U256, M256};
+use bigint::{U256, M256, Gas};
 use sputnikvm::{TransactionAction, ValidTransaction, HeaderParams, SeqTransactionVM, Patch,
                 MainnetFrontierPatch, MainnetHomesteadPatch, MainnetEIP150Patch, MainnetEIP160Patch,
-                VM, VMStatus, RequireError, AccountCommitment, AccountChange,
-                FrontierPatch, HomesteadPatch, EIP150Patch, EIP160Patch, AccountPatch};
+                MainnetEIP1283Patch,
+                VM, VMStatus, Error, RequireError, AccountCommitment, AccountChange,
+                FrontierPatch, HomesteadPatch, EIP150Patch, EIP160Patch, EIP1283Patch, AccountPatch};
 
 type c_action = c_uchar;
 #[no_mangle]
@@ -33,6 +35,18 @@ pub type MordenFrontierPatch = FrontierPatch<MordenAccountPatch>;
 pub type MordenHomesteadPatch = HomesteadPatch<MordenAccountPatch>;
 pub type MordenEIP150Patch = EIP150Patch<MordenAccountPatch>;
 pub type MordenEIP160Patch = EIP160Patch<MordenAccountPatch>;
+pub type MordenEIP1283Patch = EIP1283Patch<MordenAccountPatch>;
+
+static mut CHECKPOINTS: Option<HashMap<usize, Vec<Vec<AccountChange>>>> = None;
+
+fn checkpoints() -> &'static mut HashMap<usize, Vec<Vec<AccountChange>>> {
+    unsafe {
+        if CHECKPOINTS.is_none() {
+            CHECKPOINTS = Some(HashMap::new());
+        }
+        CHECKPOINTS.as_mut().unwrap()
+    }
+}
 
 static mut CUSTOM_INITIAL_NONCE: Option<U256> = None;
 
@@ -41,10 +55,128 @@ impl AccountPatch for CustomAccountPatch {
     fn initial_nonce() -> U256 { U256::from(unsafe { CUSTOM_INITIAL_NONCE.unwrap() }) }
 }
 
-pub type CustomFrontierPatch = FrontierPatch<CustomAccountPatch>;
-pub type CustomHomesteadPatch = HomesteadPatch<CustomAccountPatch>;
-pub type CustomEIP150Patch = EIP150Patch<CustomAccountPatch>;
-pub type CustomEIP160Patch = EIP160Patch<CustomAccountPatch>;
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct c_gas_schedule {
+    pub tier_step_gas: [u64; 8],
+    pub exp_gas: u64,
+    pub exp_byte_gas: u64,
+    pub sha3_gas: u64,
+    pub sha3_word_gas: u64,
+    pub sload_gas: u64,
+    pub sstore_set_gas: u64,
+    pub sstore_reset_gas: u64,
+    pub sstore_refund_gas: u64,
+    pub jumpdest_gas: u64,
+    pub log_gas: u64,
+    pub log_data_gas: u64,
+    pub log_topic_gas: u64,
+    pub create_gas: u64,
+    pub call_gas: u64,
+    pub call_stipend: u64,
+    pub call_value_transfer_gas: u64,
+    pub call_new_account_gas: u64,
+    pub suicide_refund_gas: u64,
+    pub stack_limit: u64,
+    pub have_delegate_call: bool,
+    pub exceptional_failed_code_deposit: bool,
+}
+
+impl Default for c_gas_schedule {
+    fn default() -> c_gas_schedule {
+        c_gas_schedule {
+            tier_step_gas: [0, 2, 3, 3, 5, 8, 10, 20],
+            exp_gas: 10,
+            exp_byte_gas: 50,
+            sha3_gas: 30,
+            sha3_word_gas: 6,
+            sload_gas: 200,
+            sstore_set_gas: 20000,
+            sstore_reset_gas: 5000,
+            sstore_refund_gas: 15000,
+            jumpdest_gas: 1,
+            log_gas: 375,
+            log_data_gas: 8,
+            log_topic_gas: 375,
+            create_gas: 32000,
+            call_gas: 700,
+            call_stipend: 2300,
+            call_value_transfer_gas: 9000,
+            call_new_account_gas: 25000,
+            suicide_refund_gas: 24000,
+            stack_limit: 1024,
+            have_delegate_call: true,
+            exceptional_failed_code_deposit: true,
+        }
+    }
+}
+
+static mut CUSTOM_GAS_SCHEDULE: Option<c_gas_schedule> = None;
+
+#[no_mangle]
+pub extern "C" fn sputnikvm_set_custom_gas_schedule(schedule: c_gas_schedule) {
+    unsafe {
+        CUSTOM_GAS_SCHEDULE = Some(schedule);
+    }
+}
+
+pub fn custom_gas_schedule() -> c_gas_schedule {
+    unsafe { CUSTOM_GAS_SCHEDULE.unwrap_or_else(c_gas_schedule::default) }
+}
+
+fn schedule_gas(v: u64) -> Gas {
+    U256::from(v).into()
+}
+
+// Unlike `CustomAccountPatch`, which only ever varies the initial nonce, these
+// four types implement `Patch` directly so every gas cost named in
+// `c_gas_schedule` comes from `sputnikvm_set_custom_gas_schedule` rather than
+// a hardcoded per-era table. Every other feature flag (REVERT/RETURNDATA/
+// STATICCALL support, the EIP-150 63/64 call-gas rule, ...) is *not* part of
+// `c_gas_schedule`, so it's forwarded to the real era patch it stands in for,
+// keeping the four eras behaviorally distinct the way `FrontierPatch`,
+// `HomesteadPatch`, `EIP150Patch` and `EIP160Patch` are.
+macro_rules! custom_patch {
+    ($name:ident, $era:ty) => {
+        pub struct $name;
+        impl Patch for $name {
+            type Account = CustomAccountPatch;
+
+            fn callstack_limit() -> usize { custom_gas_schedule().stack_limit as usize }
+            fn gas_tier_step(n: usize) -> Gas { schedule_gas(custom_gas_schedule().tier_step_gas[n]) }
+            fn gas_exp() -> Gas { schedule_gas(custom_gas_schedule().exp_gas) }
+            fn gas_exp_byte() -> Gas { schedule_gas(custom_gas_schedule().exp_byte_gas) }
+            fn gas_sha3() -> Gas { schedule_gas(custom_gas_schedule().sha3_gas) }
+            fn gas_sha3_word() -> Gas { schedule_gas(custom_gas_schedule().sha3_word_gas) }
+            fn gas_sload() -> Gas { schedule_gas(custom_gas_schedule().sload_gas) }
+            fn gas_sstore_set() -> Gas { schedule_gas(custom_gas_schedule().sstore_set_gas) }
+            fn gas_sstore_reset() -> Gas { schedule_gas(custom_gas_schedule().sstore_reset_gas) }
+            fn gas_sstore_refund() -> Gas { schedule_gas(custom_gas_schedule().sstore_refund_gas) }
+            fn gas_jumpdest() -> Gas { schedule_gas(custom_gas_schedule().jumpdest_gas) }
+            fn gas_log() -> Gas { schedule_gas(custom_gas_schedule().log_gas) }
+            fn gas_log_data() -> Gas { schedule_gas(custom_gas_schedule().log_data_gas) }
+            fn gas_log_topic() -> Gas { schedule_gas(custom_gas_schedule().log_topic_gas) }
+            fn gas_create() -> Gas { schedule_gas(custom_gas_schedule().create_gas) }
+            fn gas_call() -> Gas { schedule_gas(custom_gas_schedule().call_gas) }
+            fn gas_call_stipend() -> Gas { schedule_gas(custom_gas_schedule().call_stipend) }
+            fn gas_call_value_transfer() -> Gas { schedule_gas(custom_gas_schedule().call_value_transfer_gas) }
+            fn gas_call_new_account() -> Gas { schedule_gas(custom_gas_schedule().call_new_account_gas) }
+            fn gas_suicide_refund() -> Gas { schedule_gas(custom_gas_schedule().suicide_refund_gas) }
+            fn has_delegate_call() -> bool { custom_gas_schedule().have_delegate_call }
+            fn force_code_deposit() -> bool { custom_gas_schedule().exceptional_failed_code_deposit }
+            fn has_revert() -> bool { <$era as Patch>::has_revert() }
+            fn has_return_data() -> bool { <$era as Patch>::has_return_data() }
+            fn has_static_call() -> bool { <$era as Patch>::has_static_call() }
+            fn call_create_l64_after_gas() -> bool { <$era as Patch>::call_create_l64_after_gas() }
+        }
+    }
+}
+
+custom_patch!(CustomFrontierPatch, FrontierPatch<CustomAccountPatch>);
+custom_patch!(CustomHomesteadPatch, HomesteadPatch<CustomAccountPatch>);
+custom_patch!(CustomEIP150Patch, EIP150Patch<CustomAccountPatch>);
+custom_patch!(CustomEIP160Patch, EIP160Patch<CustomAccountPatch>);
+pub type CustomEIP1283Patch = EIP1283Patch<CustomAccountPatch>;
 
 #[repr(C)]
 pub struct c_transaction {
@@ -80,6 +212,7 @@ pub enum c_require_type {
     account,
     account_code,
     account_storage,
+    account_original_storage,
     blockhash
 }
 
@@ -87,6 +220,7 @@ pub enum c_require_type {
 pub union c_require_value {
     pub account: c_address,
     pub account_storage: c_require_value_account_storage,
+    pub account_original_storage: c_require_value_account_storage,
     pub blockhash: c_u256,
 }
 
@@ -291,11 +425,33 @@ pub extern "C" fn sputnikvm_new_custom_eip160(
     sputnikvm_new::<CustomEIP160Patch>(transaction, header)
 }
 
+#[no_mangle]
+pub extern "C" fn sputnikvm_new_eip1283(
+    transaction: c_transaction, header: c_header_params
+) -> *mut Box<VM> {
+    sputnikvm_new::<MainnetEIP1283Patch>(transaction, header)
+}
+
+#[no_mangle]
+pub extern "C" fn sputnikvm_new_morden_eip1283(
+    transaction: c_transaction, header: c_header_params
+) -> *mut Box<VM> {
+    sputnikvm_new::<MordenEIP1283Patch>(transaction, header)
+}
+
+#[no_mangle]
+pub extern "C" fn sputnikvm_new_custom_eip1283(
+    transaction: c_transaction, header: c_header_params
+) -> *mut Box<VM> {
+    sputnikvm_new::<CustomEIP1283Patch>(transaction, header)
+}
+
 #[no_mangle]
 pub extern "C" fn sputnikvm_free(
     vm: *mut Box<VM>
 ) {
     if vm.is_null() { return; }
+    checkpoints().remove(&(vm as usize));
     unsafe { Box::from_raw(vm); }
 }
 
@@ -343,6 +499,17 @@ pub extern "C" fn sputnikvm_fire(
                     }
                 };
             },
+            Err(RequireError::AccountOriginalStorage(address, key)) => {
+                ret = c_require {
+                    typ: c_require_type::account_original_storage,
+                    value: c_require_value {
+                        account_original_storage: c_require_value_account_storage {
+                            address: address.into(),
+                            key: key.into(),
+                        },
+                    }
+                };
+            },
             Err(RequireError::Blockhash(number)) => {
                 ret = c_require {
                     typ: c_require_type::blockhash,
@@ -357,6 +524,71 @@ pub extern "C" fn sputnikvm_fire(
     ret
 }
 
+// Snapshots the account-change overlay accumulated so far, keyed by the VM's
+// own address, and returns its index on that VM's checkpoint stack. The
+// snapshots are only used for the stack bookkeeping of
+// `sputnikvm_discard_checkpoint` and `sputnikvm_revert_to_checkpoint`; no
+// function here returns a snapshot's contents. See
+// `sputnikvm_revert_to_checkpoint` for why the snapshot itself can't be
+// replayed back into the VM.
+#[no_mangle]
+pub extern "C" fn sputnikvm_checkpoint(
+    vm: *mut Box<VM>
+) -> c_uint {
+    let mut vm_box = unsafe { Box::from_raw(vm) };
+    let idx;
+    {
+        let vmref: &mut VM = vm_box.deref_mut().deref_mut();
+        let snapshot: Vec<AccountChange> = vmref.accounts().cloned().collect();
+        let stack = checkpoints().entry(vm as usize).or_insert_with(Vec::new);
+        stack.push(snapshot);
+        idx = (stack.len() - 1) as c_uint;
+    }
+    Box::into_raw(vm_box);
+    idx
+}
+
+// `VM::commit_account` only ever feeds state the VM doesn't already know
+// about in response to a `RequireError` — committing an address the VM has
+// already touched returns `Err` and changes nothing, so replaying it can't
+// undo balances, nonces or storage the VM already mutated internally, and it
+// has no effect at all on the refund counter. The `VM` trait exposed to this
+// FFI (`fire`, `commit_account`, `commit_blockhash`, `accounts`, `logs`,
+// `status`, `out`, `used_gas`) has no method that overwrites a live VM's
+// internal state, so true in-place revert-to-checkpoint is not implementable
+// against this trait. Rather than pretend to roll back and silently leave
+// the VM's real state untouched, this reports failure: callers that need
+// genuine speculative "what-if" execution or nested-call rollback must run
+// the branch in its own `VM` instance (via `sputnikvm_new_*`) and simply
+// drop it on failure, rather than reusing one `VM` across branches.
+#[no_mangle]
+pub extern "C" fn sputnikvm_revert_to_checkpoint(
+    vm: *mut Box<VM>, idx: c_uint
+) -> bool {
+    if let Some(stack) = checkpoints().get_mut(&(vm as usize)) {
+        stack.truncate(idx as usize + 1);
+    }
+    false
+}
+
+// Drops checkpoint `idx`, folding its snapshot into its parent frame so the
+// child's changes survive the discard, mirroring how a successful nested
+// CALL/CREATE keeps its state when the call stack unwinds.
+#[no_mangle]
+pub extern "C" fn sputnikvm_discard_checkpoint(
+    vm: *mut Box<VM>, idx: c_uint
+) {
+    if let Some(stack) = checkpoints().get_mut(&(vm as usize)) {
+        let idx = idx as usize;
+        if idx < stack.len() {
+            let child = stack.remove(idx);
+            if idx > 0 {
+                stack[idx - 1] = child;
+            }
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn sputnikvm_commit_account(
     vm: *mut Box<VM>, address: c_address, nonce: c_u256, balance: c_u256,
@@ -433,6 +665,31 @@ pub extern "C" fn sputnikvm_commit_account_storage(
     ret
 }
 
+#[no_mangle]
+pub extern "C" fn sputnikvm_commit_account_original_storage(
+    vm: *mut Box<VM>, address: c_address, index: c_u256, value: c_u256
+) -> bool {
+    let mut vm_box = unsafe { Box::from_raw(vm) };
+    let ret;
+    {
+        let vm: &mut VM = vm_box.deref_mut().deref_mut();
+        let commitment = AccountCommitment::StorageOriginal {
+            address: address.into(),
+            index: index.into(),
+            value: {
+                let value: U256 = value.into();
+                value.into()
+            },
+        };
+        match vm.commit_account(commitment) {
+            Ok(()) => { ret = true; }
+            Err(_) => { ret = false; }
+        }
+    }
+    Box::into_raw(vm_box);
+    ret
+}
+
 #[no_mangle]
 pub extern "C" fn sputnikvm_commit_nonexist(
     vm: *mut Box<VM>, address: c_address
@@ -727,6 +984,144 @@ pub extern "C" fn sputnikvm_account_changes_copy_code(
     ret
 }
 
+fn account_change_address(account: &AccountChange) -> bigint::Address {
+    match account {
+        &AccountChange::Full { address, .. } => address,
+        &AccountChange::Create { address, .. } => address,
+        &AccountChange::Nonexist(address) => address,
+        &AccountChange::IncreaseBalance(address, _) => address,
+        &AccountChange::DecreaseBalance(address, _) => address,
+    }
+}
+
+// Like `sputnikvm_account_changes_copy_info`, but sorted by address so a
+// host can diff two snapshots byte-for-byte instead of reconciling
+// HashMap-ordered output.
+#[no_mangle]
+pub extern "C" fn sputnikvm_export_state(
+    vm: *mut Box<VM>, w: *mut c_account_change, wl: c_uint
+) {
+    let mut vm_box = unsafe { Box::from_raw(vm) };
+    {
+        let vm: &mut VM = vm_box.deref_mut().deref_mut();
+        let mut accounts: Vec<&AccountChange> = vm.accounts().collect();
+        accounts.sort_by_key(|account| account_change_address(account));
+        let mut w = unsafe { slice::from_raw_parts_mut(w, wl as usize) };
+        for (i, account) in accounts.into_iter().enumerate() {
+            if i < w.len() {
+                w[i] = match account {
+                    &AccountChange::Full { nonce, address, balance, ref changing_storage, ref code } => {
+                        c_account_change {
+                            typ: c_account_change_type::full,
+                            value: c_account_change_value {
+                                all: c_account_change_value_all {
+                                    address: address.into(),
+                                    nonce: nonce.into(),
+                                    balance: balance.into(),
+                                    storage_len: changing_storage.len() as c_uint,
+                                    code_len: code.len() as c_uint,
+                                },
+                            },
+                        }
+                    },
+                    &AccountChange::Create { nonce, address, balance, ref storage, ref code } => {
+                        c_account_change {
+                            typ: c_account_change_type::create,
+                            value: c_account_change_value {
+                                all: c_account_change_value_all {
+                                    address: address.into(),
+                                    nonce: nonce.into(),
+                                    balance: balance.into(),
+                                    storage_len: storage.len() as c_uint,
+                                    code_len: code.len() as c_uint,
+                                },
+                            },
+                        }
+                    },
+                    &AccountChange::Nonexist(address) => {
+                        c_account_change {
+                            typ: c_account_change_type::removed,
+                            value: c_account_change_value {
+                                removed: address.into(),
+                            },
+                        }
+                    },
+                    &AccountChange::IncreaseBalance(address, amount) => {
+                        c_account_change {
+                            typ: c_account_change_type::increase_balance,
+                            value: c_account_change_value {
+                                balance: c_account_change_value_balance {
+                                    address: address.into(),
+                                    amount: amount.into(),
+                                },
+                            }
+                        }
+                    },
+                    &AccountChange::DecreaseBalance(address, amount) => {
+                        c_account_change {
+                            typ: c_account_change_type::decrease_balance,
+                            value: c_account_change_value {
+                                balance: c_account_change_value_balance {
+                                    address: address.into(),
+                                    amount: amount.into(),
+                                },
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+    Box::into_raw(vm_box);
+}
+
+// Like `sputnikvm_account_changes_copy_storage`, but the (key, value) pairs
+// are sorted by key so repeated exports of the same account are byte-stable,
+// matching a PodState's deterministic storage ordering.
+#[no_mangle]
+pub extern "C" fn sputnikvm_export_account_storage(
+    vm: *mut Box<VM>, address: c_address, w: *mut c_account_change_storage, wl: c_uint
+) -> bool {
+    let mut vm_box = unsafe { Box::from_raw(vm) };
+    let mut ret = false;
+    {
+        let vm: &mut VM = vm_box.deref_mut().deref_mut();
+        let accounts = vm.accounts();
+        let target_address = address.into();
+        for account in accounts {
+            let storage: Option<HashMap<U256, M256>> = match account {
+                &AccountChange::Full { address, ref changing_storage, .. } if address == target_address => {
+                    Some(changing_storage.clone().into())
+                },
+                &AccountChange::Create { address, ref storage, .. } if address == target_address => {
+                    Some(storage.clone().into())
+                },
+                _ => None,
+            };
+            if let Some(storage) = storage {
+                let mut pairs: Vec<(U256, M256)> = storage.into_iter().collect();
+                pairs.sort_by_key(|&(key, _)| key);
+                let mut w = unsafe { slice::from_raw_parts_mut(w, wl as usize) };
+                for (i, (key, value)) in pairs.into_iter().enumerate() {
+                    if i < w.len() {
+                        w[i] = c_account_change_storage {
+                            key: key.into(),
+                            value: {
+                                let u: U256 = value.into();
+                                u.into()
+                            }
+                        };
+                    }
+                }
+                ret = true;
+                break;
+            }
+        }
+    }
+    Box::into_raw(vm_box);
+    ret
+}
+
 #[no_mangle]
 pub extern "C" fn sputnikvm_used_gas(vm: *mut Box<VM>) -> c_gas {
     let mut vm_box = unsafe { Box::from_raw(vm) };
@@ -779,3 +1174,68 @@ pub extern "C" fn sputnikvm_status_failed(vm: *mut Box<VM>) -> c_uchar {
     Box::into_raw(vm_box);
     ret
 }
+
+#[repr(C)]
+pub enum c_vm_status {
+    running,
+    exited_ok,
+    exited_revert,
+    exited_out_of_gas,
+    exited_invalid_opcode,
+    exited_stack_underflow,
+    exited_stack_overflow,
+    exited_invalid_jump_destination,
+    exited_not_supported,
+    exited_other,
+}
+
+#[no_mangle]
+pub extern "C" fn sputnikvm_status(vm: *mut Box<VM>) -> c_vm_status {
+    let mut vm_box = unsafe { Box::from_raw(vm) };
+    let ret;
+    {
+        let vm: &mut VM = vm_box.deref_mut().deref_mut();
+        ret = match vm.status() {
+            VMStatus::Running => c_vm_status::running,
+            VMStatus::ExitedOk => c_vm_status::exited_ok,
+            VMStatus::ExitedErr(Error::Revert) => c_vm_status::exited_revert,
+            VMStatus::ExitedErr(Error::OutOfGas) => c_vm_status::exited_out_of_gas,
+            VMStatus::ExitedErr(Error::InvalidOpcode) => c_vm_status::exited_invalid_opcode,
+            VMStatus::ExitedErr(Error::StackUnderflow) => c_vm_status::exited_stack_underflow,
+            VMStatus::ExitedErr(Error::StackOverflow) => c_vm_status::exited_stack_overflow,
+            VMStatus::ExitedErr(Error::InvalidJumpDest) => c_vm_status::exited_invalid_jump_destination,
+            VMStatus::ExitedErr(Error::NotSupported) => c_vm_status::exited_not_supported,
+            VMStatus::ExitedErr(_) => c_vm_status::exited_other,
+        };
+    }
+    Box::into_raw(vm_box);
+    ret
+}
+
+#[no_mangle]
+pub extern "C" fn sputnikvm_revert_data_len(vm: *mut Box<VM>) -> c_uint {
+    let mut vm_box = unsafe { Box::from_raw(vm) };
+    let ret;
+    {
+        let vm: &mut VM = vm_box.deref_mut().deref_mut();
+        ret = vm.out().len() as c_uint;
+    }
+    Box::into_raw(vm_box);
+    ret
+}
+
+#[no_mangle]
+pub extern "C" fn sputnikvm_revert_data_copy(vm: *mut Box<VM>, data_w: *mut u8, data_w_len: c_uint) {
+    let mut vm_box = unsafe { Box::from_raw(vm) };
+    {
+        let vm: &mut VM = vm_box.deref_mut().deref_mut();
+        let out = vm.out();
+        let mut data_w = unsafe { slice::from_raw_parts_mut(data_w, data_w_len as usize) };
+        for i in 0..data_w.len() {
+            if i < out.len() {
+                data_w[i] = out[i];
+            }
+        }
+    }
+    Box::into_raw(vm_box);
+}
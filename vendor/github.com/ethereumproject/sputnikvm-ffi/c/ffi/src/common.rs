@@ -1,5 +1,7 @@
+use std::slice;
 use libc::{c_uchar};
 use bigint::{U256, H256, Gas, Address};
+use tiny_keccak::Keccak;
 
 // We use big-endian representation for c_u256 and c_gas. Note that
 // however, in etcommon-bigint, it is little-endian representation.
@@ -139,3 +141,419 @@ impl From<H256> for c_h256 {
     }
 }
 
+// A `c_h256` is a keccak hash; a contract/CREATE address is derived from one
+// by taking the low 20 bytes. A `c_u256` and a `c_h256` share the same
+// big-endian 32-byte layout, so a storage key can be read as either.
+
+impl From<c_h256> for c_address {
+    fn from(val: c_h256) -> Self {
+        let mut a = c_address::default();
+        a.data.copy_from_slice(&val.data[12..32]);
+        a
+    }
+}
+
+impl From<c_address> for c_h256 {
+    fn from(val: c_address) -> Self {
+        let mut a = c_h256::default();
+        a.data[12..32].copy_from_slice(&val.data);
+        a
+    }
+}
+
+impl From<c_h256> for c_u256 {
+    fn from(val: c_h256) -> Self {
+        c_u256 { data: val.data }
+    }
+}
+
+impl From<c_u256> for c_h256 {
+    fn from(val: c_u256) -> Self {
+        c_h256 { data: val.data }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn c_h256_to_address(val: c_h256) -> c_address {
+    val.into()
+}
+
+#[no_mangle]
+pub extern "C" fn c_address_to_h256(val: c_address) -> c_h256 {
+    val.into()
+}
+
+#[no_mangle]
+pub extern "C" fn c_h256_to_u256(val: c_h256) -> c_u256 {
+    val.into()
+}
+
+#[no_mangle]
+pub extern "C" fn c_u256_to_h256(val: c_u256) -> c_h256 {
+    val.into()
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct c_u512 {
+    pub data: [c_uchar; 64],
+}
+
+impl Default for c_u512 {
+    fn default() -> c_u512 {
+        c_u512 {
+            data: [0; 64]
+        }
+    }
+}
+
+fn be_bytes_to_u64(bytes: &[u8]) -> u64 {
+    let mut v: u64 = 0;
+    for i in 0..8 {
+        v = (v << 8) | (bytes[i] as u64);
+    }
+    v
+}
+
+fn u64_to_be_bytes(v: u64, out: &mut [u8]) {
+    for i in 0..8 {
+        out[i] = ((v >> (8 * (7 - i))) & 0xff) as u8;
+    }
+}
+
+fn add_carry(limbs: &mut [u64; 8], mut idx: usize, mut carry: u128) {
+    while carry > 0 {
+        let sum = limbs[idx] as u128 + carry;
+        limbs[idx] = sum as u64;
+        carry = sum >> 64;
+        idx += 1;
+    }
+}
+
+// Schoolbook 256x256->512 multiplication over 64-bit limbs, needed for
+// MULMOD/EXP intermediates that a `c_u256`-only surface can't express
+// without losing the high bits.
+#[no_mangle]
+pub extern "C" fn c_u256_full_mul(a: c_u256, b: c_u256) -> c_u512 {
+    let mut a_limbs = [0u64; 4];
+    let mut b_limbs = [0u64; 4];
+    for i in 0..4 {
+        a_limbs[i] = be_bytes_to_u64(&a.data[32 - (i + 1) * 8..32 - i * 8]);
+        b_limbs[i] = be_bytes_to_u64(&b.data[32 - (i + 1) * 8..32 - i * 8]);
+    }
+
+    let mut result = [0u64; 8];
+    for i in 0..4 {
+        let mut carry: u128 = 0;
+        for j in 0..4 {
+            let idx = i + j;
+            let prod = (a_limbs[i] as u128) * (b_limbs[j] as u128)
+                + (result[idx] as u128) + carry;
+            result[idx] = prod as u64;
+            carry = prod >> 64;
+        }
+        add_carry(&mut result, i + 4, carry);
+    }
+
+    let mut out = c_u512::default();
+    for i in 0..8 {
+        let limb = result[7 - i];
+        let start = i * 8;
+        u64_to_be_bytes(limb, &mut out.data[start..start + 8]);
+    }
+    out
+}
+
+// Bridges these byte-buffer FFI types to a word-oriented JIT, which
+// represents a 256-bit value as four native `u64` limbs in machine
+// (little-endian limb) order — `words[0]` is the *least*-significant 64
+// bits, `words[3]` the most-significant — rather than 32 big-endian bytes.
+// Each limb is itself filled from 8 big-endian buffer bytes, since the
+// buffer's byte order doesn't change; only which 8-byte chunk maps to which
+// limb index does.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct c_i256 {
+    pub words: [u64; 4],
+}
+
+impl Default for c_i256 {
+    fn default() -> c_i256 {
+        c_i256 {
+            words: [0; 4]
+        }
+    }
+}
+
+pub trait IntoJit {
+    fn into_jit(self) -> c_i256;
+}
+
+pub trait FromJit {
+    fn from_jit(val: c_i256) -> Self;
+}
+
+impl IntoJit for c_u256 {
+    fn into_jit(self) -> c_i256 {
+        let mut words = [0u64; 4];
+        for i in 0..4 {
+            words[i] = be_bytes_to_u64(&self.data[(3 - i) * 8..(3 - i) * 8 + 8]);
+        }
+        c_i256 { words: words }
+    }
+}
+
+impl FromJit for c_u256 {
+    fn from_jit(val: c_i256) -> c_u256 {
+        let mut data = [0u8; 32];
+        for i in 0..4 {
+            u64_to_be_bytes(val.words[i], &mut data[(3 - i) * 8..(3 - i) * 8 + 8]);
+        }
+        c_u256 { data: data }
+    }
+}
+
+impl IntoJit for c_h256 {
+    fn into_jit(self) -> c_i256 {
+        let mut words = [0u64; 4];
+        for i in 0..4 {
+            words[i] = be_bytes_to_u64(&self.data[(3 - i) * 8..(3 - i) * 8 + 8]);
+        }
+        c_i256 { words: words }
+    }
+}
+
+impl FromJit for c_h256 {
+    fn from_jit(val: c_i256) -> c_h256 {
+        let mut data = [0u8; 32];
+        for i in 0..4 {
+            u64_to_be_bytes(val.words[i], &mut data[(3 - i) * 8..(3 - i) * 8 + 8]);
+        }
+        c_h256 { data: data }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn c_u256_into_jit(val: c_u256) -> c_i256 {
+    val.into_jit()
+}
+
+#[no_mangle]
+pub extern "C" fn c_u256_from_jit(val: c_i256) -> c_u256 {
+    c_u256::from_jit(val)
+}
+
+#[no_mangle]
+pub extern "C" fn c_h256_into_jit(val: c_h256) -> c_i256 {
+    val.into_jit()
+}
+
+#[no_mangle]
+pub extern "C" fn c_h256_from_jit(val: c_i256) -> c_h256 {
+    c_h256::from_jit(val)
+}
+
+// A 2048-bit Ethereum logs bloom. Each accrued value sets three bits, one
+// per 16-bit big-endian word taken from its keccak256 hash.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct c_bloom {
+    pub data: [c_uchar; 256],
+}
+
+impl Default for c_bloom {
+    fn default() -> c_bloom {
+        c_bloom {
+            data: [0; 256]
+        }
+    }
+}
+
+fn bloom_bit_indexes(input: &[u8]) -> [usize; 3] {
+    let mut keccak = Keccak::new_keccak256();
+    keccak.update(input);
+    let mut hash = [0u8; 32];
+    keccak.finalize(&mut hash);
+
+    let mut indexes = [0usize; 3];
+    for i in 0..3 {
+        let m = ((hash[i * 2] as usize) << 8) | (hash[i * 2 + 1] as usize);
+        indexes[i] = m & 0x7FF;
+    }
+    indexes
+}
+
+// `index` is a bit position in [0, 2048) as produced by `bloom_bit_indexes`. The 256-byte
+// array is treated as a big-endian 2048-bit integer, matching go-ethereum's bloom layout
+// (`Bloom.setBytes`/`Bloom.add`), so that blooms built here agree byte-for-byte with
+// blooms from receipts and block headers produced by other Ethereum clients.
+fn bloom_set(bloom: &mut c_bloom, index: usize) {
+    bloom.data[255 - index / 8] |= 1 << (index % 8);
+}
+
+fn bloom_is_set(bloom: &c_bloom, index: usize) -> bool {
+    bloom.data[255 - index / 8] & (1 << (index % 8)) != 0
+}
+
+#[no_mangle]
+pub extern "C" fn c_bloom_accrue(bloom: *mut c_bloom, input: *const u8, len: usize) {
+    let data = unsafe { slice::from_raw_parts(input, len) };
+    let bloom = unsafe { &mut *bloom };
+    for &index in bloom_bit_indexes(data).iter() {
+        bloom_set(bloom, index);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn c_bloom_contains(bloom: *const c_bloom, input: *const u8, len: usize) -> bool {
+    let data = unsafe { slice::from_raw_parts(input, len) };
+    let bloom = unsafe { &*bloom };
+    bloom_bit_indexes(data).iter().all(|&index| bloom_is_set(bloom, index))
+}
+
+#[no_mangle]
+pub extern "C" fn c_bloom_accrue_bloom(bloom: *mut c_bloom, other: *const c_bloom) {
+    let bloom = unsafe { &mut *bloom };
+    let other = unsafe { &*other };
+    for i in 0..256 {
+        bloom.data[i] |= other.data[i];
+    }
+}
+
+// Hex parsing/formatting for the FFI value types, since host integrations
+// (JSON-RPC glue, test harnesses) overwhelmingly deal in `0x`-prefixed hex.
+
+#[repr(C)]
+pub enum c_hex_status {
+    ok,
+    invalid_length,
+    invalid_character,
+}
+
+fn hex_val(c: u8) -> Option<u8> {
+    match c {
+        b'0'...b'9' => Some(c - b'0'),
+        b'a'...b'f' => Some(c - b'a' + 10),
+        b'A'...b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+// Parses `input` (optionally `0x`-prefixed, optionally odd-length) into a
+// fixed-width big-endian byte buffer, left-padding as needed.
+fn parse_hex(input: &[u8], out: &mut [u8]) -> c_hex_status {
+    let s: &[u8] = if input.len() >= 2 && input[0] == b'0' && (input[1] == b'x' || input[1] == b'X') {
+        &input[2..]
+    } else {
+        input
+    };
+
+    if s.len() > out.len() * 2 {
+        return c_hex_status::invalid_length;
+    }
+
+    for b in out.iter_mut() {
+        *b = 0;
+    }
+
+    let mut nibble_pos = s.len();
+    let mut byte_idx = out.len();
+    while nibble_pos > 0 {
+        let lo = match hex_val(s[nibble_pos - 1]) {
+            Some(v) => v,
+            None => return c_hex_status::invalid_character,
+        };
+        let hi = if nibble_pos >= 2 {
+            match hex_val(s[nibble_pos - 2]) {
+                Some(v) => v,
+                None => return c_hex_status::invalid_character,
+            }
+        } else {
+            0
+        };
+        byte_idx -= 1;
+        out[byte_idx] = (hi << 4) | lo;
+        nibble_pos = if nibble_pos >= 2 { nibble_pos - 2 } else { 0 };
+    }
+
+    c_hex_status::ok
+}
+
+fn write_hex(bytes: &[u8], out: *mut c_uchar, out_len: usize) {
+    const HEX_CHARS: &'static [u8; 16] = b"0123456789abcdef";
+    let out = unsafe { slice::from_raw_parts_mut(out, out_len) };
+    for i in 0..out.len() {
+        if i < bytes.len() * 2 {
+            let byte = bytes[i / 2];
+            let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0xf };
+            out[i] = HEX_CHARS[nibble as usize];
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn c_u256_from_hex(hex: *const c_uchar, hex_len: usize, out: *mut c_u256) -> c_hex_status {
+    let input = unsafe { slice::from_raw_parts(hex, hex_len) };
+    let mut buf = [0u8; 32];
+    let status = parse_hex(input, &mut buf);
+    if let c_hex_status::ok = status {
+        unsafe { (*out).data = buf; }
+    }
+    status
+}
+
+#[no_mangle]
+pub extern "C" fn c_u256_to_hex(val: c_u256, out: *mut c_uchar, out_len: usize) {
+    write_hex(&val.data, out, out_len);
+}
+
+#[no_mangle]
+pub extern "C" fn c_h256_from_hex(hex: *const c_uchar, hex_len: usize, out: *mut c_h256) -> c_hex_status {
+    let input = unsafe { slice::from_raw_parts(hex, hex_len) };
+    let mut buf = [0u8; 32];
+    let status = parse_hex(input, &mut buf);
+    if let c_hex_status::ok = status {
+        unsafe { (*out).data = buf; }
+    }
+    status
+}
+
+#[no_mangle]
+pub extern "C" fn c_h256_to_hex(val: c_h256, out: *mut c_uchar, out_len: usize) {
+    write_hex(&val.data, out, out_len);
+}
+
+#[no_mangle]
+pub extern "C" fn c_address_from_hex(hex: *const c_uchar, hex_len: usize, out: *mut c_address) -> c_hex_status {
+    let input = unsafe { slice::from_raw_parts(hex, hex_len) };
+    let mut buf = [0u8; 20];
+    let status = parse_hex(input, &mut buf);
+    if let c_hex_status::ok = status {
+        unsafe { (*out).data = buf; }
+    }
+    status
+}
+
+#[no_mangle]
+pub extern "C" fn c_address_to_hex(val: c_address, out: *mut c_uchar, out_len: usize) {
+    write_hex(&val.data, out, out_len);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u256_into_jit_is_little_endian_limb_order() {
+        let mut data = [0u8; 32];
+        data[31] = 1;
+        let val = c_u256 { data: data };
+
+        let jit = val.into_jit();
+        assert_eq!(jit.words, [1, 0, 0, 0]);
+
+        let back = c_u256::from_jit(jit);
+        assert_eq!(back.data[..], data[..]);
+    }
+}
+